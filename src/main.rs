@@ -13,9 +13,13 @@ extern crate log;
 extern crate env_logger;
 #[macro_use]
 extern crate structopt;
+extern crate dotenv;
+extern crate serde_yaml;
+extern crate trust_dns_resolver;
 
 use failure::Error;
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 #[derive(Deserialize, Debug)]
 struct IpifyResponse {
@@ -24,10 +28,50 @@ struct IpifyResponse {
 
 header! { (PddToken, "PddToken") => [String] }
 
-fn real_ip() -> Result<IpAddr, Error> {
-    let response: IpifyResponse = reqwest::get("https://api.ipify.org?format=json")?.json()?;
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+enum RecordType {
+    A,
+    #[serde(rename = "AAAA")]
+    Aaaa,
+}
+
+impl RecordType {
+    /// The record type appropriate for an address of this family.
+    fn of_ip(ip: &IpAddr) -> RecordType {
+        match ip {
+            IpAddr::V4(_) => RecordType::A,
+            IpAddr::V6(_) => RecordType::Aaaa,
+        }
+    }
+
+    /// The value expected by the Yandex `type` query parameter.
+    fn as_str(self) -> &'static str {
+        match self {
+            RecordType::A => "A",
+            RecordType::Aaaa => "AAAA",
+        }
+    }
+}
+
+impl ::std::str::FromStr for RecordType {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<RecordType, Error> {
+        match s.to_uppercase().as_str() {
+            "A" => Ok(RecordType::A),
+            "AAAA" => Ok(RecordType::Aaaa),
+            other => bail!("Unknown record type {:?}", other),
+        }
+    }
+}
+
+fn real_ip(record_type: RecordType) -> Result<IpAddr, Error> {
+    let url = match record_type {
+        RecordType::A => "https://api.ipify.org?format=json",
+        RecordType::Aaaa => "https://api6.ipify.org?format=json",
+    };
+    let response: IpifyResponse = reqwest::get(url)?.json()?;
     let ip = response.ip;
-    debug!("Real IP is {:?}", ip);
+    debug!("Real {} IP is {:?}", record_type.as_str(), ip);
     Ok(ip)
 }
 
@@ -37,6 +81,10 @@ struct Record {
     id: i64,
     content: String,
     subdomain: String,
+    #[serde(rename = "type")]
+    record_type: RecordType,
+    #[serde(default)]
+    ttl: u32,
 }
 
 fn find_all_records(token: &str, domain: &str) -> Result<Vec<Record>, Error> {
@@ -62,23 +110,120 @@ fn find_all_records(token: &str, domain: &str) -> Result<Vec<Record>, Error> {
     }
 }
 
-fn find_record(token: &str, domain: &str, subdomain: &str) -> Result<Record, Error> {
+fn find_record(
+    token: &str,
+    domain: &str,
+    subdomain: &str,
+    record_type: RecordType,
+) -> Result<Record, Error> {
     match find_all_records(token, domain)?
         .into_iter()
-        .find(|record| record.subdomain == subdomain)
+        .find(|record| record.subdomain == subdomain && record.record_type == record_type)
     {
         Some(record) => Ok(record),
-        None => bail!("No record for subdomain {:?}", subdomain),
+        None => bail!(
+            "No {} record for subdomain {:?}",
+            record_type.as_str(),
+            subdomain
+        ),
     }
 }
 
-fn current_ip(token: &str, domain: &str, subdomain: &str) -> Result<IpAddr, Error> {
-    let ip: IpAddr = find_record(token, domain, subdomain)?.content.parse()?;
+fn current_ip(
+    token: &str,
+    domain: &str,
+    subdomain: &str,
+    record_type: RecordType,
+) -> Result<IpAddr, Error> {
+    let ip: IpAddr = find_record(token, domain, subdomain, record_type)?
+        .content
+        .parse()?;
     debug!("Current IP is {:?}", ip);
     Ok(ip)
 }
 
-fn set_ip(token: &str, domain: &str, subdomain: &str, ip: IpAddr) -> Result<(), Error> {
+/// Resolve `fqdn` over DNS and return its current address, or `None` when the
+/// name does not exist yet (NXDOMAIN / empty answer). This reflects what
+/// clients actually see after propagation and avoids an authenticated list
+/// call on every poll; the admin API is only touched when an edit turns out to
+/// be necessary.
+fn resolved_ip(fqdn: &str, record_type: RecordType) -> Result<Option<IpAddr>, Error> {
+    use trust_dns_resolver::config::{LookupIpStrategy, ResolverConfig, ResolverOpts};
+    use trust_dns_resolver::error::ResolveErrorKind;
+    use trust_dns_resolver::Resolver;
+    let mut opts = ResolverOpts::default();
+    opts.ip_strategy = match record_type {
+        RecordType::A => LookupIpStrategy::Ipv4Only,
+        RecordType::Aaaa => LookupIpStrategy::Ipv6Only,
+    };
+    let resolver = Resolver::new(ResolverConfig::default(), opts)?;
+    let lookup = match resolver.lookup_ip(fqdn) {
+        Ok(lookup) => lookup,
+        Err(error) => match error.kind() {
+            ResolveErrorKind::NoRecordsFound { .. } => return Ok(None),
+            _ => return Err(error.into()),
+        },
+    };
+    let ip = lookup
+        .iter()
+        .find(|ip| RecordType::of_ip(ip) == record_type);
+    if let Some(ip) = ip {
+        debug!("Resolved {} to {:?}", fqdn, ip);
+    }
+    Ok(ip)
+}
+
+/// Like [`update`], but reads the current value straight from DNS instead of
+/// the Yandex list API. An NXDOMAIN answer is treated as a missing record,
+/// which is created with the `dns/add` call.
+///
+/// Note: this does not pace polling against the record TTL — the caller (e.g.
+/// the `watch` loop) drives the interval, so a tight `delay_between_check` can
+/// still query faster than caches refresh. TTL-based pacing is not implemented.
+fn update_resolved(config: &Config, record: &RecordConfig) -> Result<(), Error> {
+    let fqdn = format!("{}.{}", record.subdomain, config.domain);
+    let real_ip = real_ip(record.record_type)?;
+    match resolved_ip(&fqdn, record.record_type)? {
+        Some(current_ip) if current_ip == real_ip => {
+            debug!("Resolved IP is same as real IP: {:?}", current_ip);
+        }
+        Some(current_ip) => {
+            info!(
+                "Resolved {:?} differs from real {:?}, updating",
+                current_ip, real_ip
+            );
+            set_ip(
+                &config.token,
+                &config.domain,
+                &record.subdomain,
+                record.record_type,
+                real_ip,
+                record.ttl,
+            )?;
+        }
+        None => {
+            info!("No record for {}, adding {:?}", fqdn, real_ip);
+            add_record(
+                &config.token,
+                &config.domain,
+                &record.subdomain,
+                record.record_type,
+                real_ip,
+                record.ttl,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn set_ip(
+    token: &str,
+    domain: &str,
+    subdomain: &str,
+    record_type: RecordType,
+    ip: IpAddr,
+    ttl: Option<u32>,
+) -> Result<(), Error> {
     #[derive(Deserialize, Debug)]
     #[serde(tag = "success")]
     enum YandexUpdateResponse {
@@ -87,76 +232,316 @@ fn set_ip(token: &str, domain: &str, subdomain: &str, ip: IpAddr) -> Result<(),
         #[serde(rename = "error")]
         Error { error: String },
     }
-    let record_id = find_record(token, domain, subdomain)?.id;
+    let record_id = find_record(token, domain, subdomain, record_type)?.id;
+    let mut query = vec![
+        ("domain", domain.to_owned()),
+        ("record_id", record_id.to_string()),
+        ("content", ip.to_string()),
+    ];
+    if let Some(ttl) = ttl {
+        query.push(("ttl", ttl.to_string()));
+    }
     let client = reqwest::Client::new();
     let response: YandexUpdateResponse = client
         .post("https://pddimp.yandex.ru/api2/admin/dns/edit")
         .header(PddToken(token.to_owned()))
+        .query(&query)
+        .send()?
+        .json()?;
+    trace!("{:?}", response);
+    match response {
+        YandexUpdateResponse::Ok { record } => {
+            info!("IP has been set to {:?}", record.content);
+            Ok(())
+        }
+        YandexUpdateResponse::Error { error } => bail!("Update failed: {}", error),
+    }
+}
+
+fn add_record(
+    token: &str,
+    domain: &str,
+    subdomain: &str,
+    record_type: RecordType,
+    ip: IpAddr,
+    ttl: Option<u32>,
+) -> Result<(), Error> {
+    #[derive(Deserialize, Debug)]
+    #[serde(tag = "success")]
+    enum YandexUpdateResponse {
+        #[serde(rename = "ok")]
+        Ok { record: Record },
+        #[serde(rename = "error")]
+        Error { error: String },
+    }
+    let mut query = vec![
+        ("domain", domain.to_owned()),
+        ("type", record_type.as_str().to_owned()),
+        ("subdomain", subdomain.to_owned()),
+        ("content", ip.to_string()),
+    ];
+    if let Some(ttl) = ttl {
+        query.push(("ttl", ttl.to_string()));
+    }
+    let client = reqwest::Client::new();
+    let response: YandexUpdateResponse = client
+        .post("https://pddimp.yandex.ru/api2/admin/dns/add")
+        .header(PddToken(token.to_owned()))
+        .query(&query)
+        .send()?
+        .json()?;
+    trace!("{:?}", response);
+    match response {
+        YandexUpdateResponse::Ok { record } => {
+            info!("Added record {:?} -> {:?}", record.subdomain, record.content);
+            Ok(())
+        }
+        YandexUpdateResponse::Error { error } => bail!("Add failed: {}", error),
+    }
+}
+
+fn delete_record(
+    token: &str,
+    domain: &str,
+    subdomain: &str,
+    record_type: RecordType,
+) -> Result<(), Error> {
+    #[derive(Deserialize, Debug)]
+    #[serde(tag = "success")]
+    enum YandexDeleteResponse {
+        #[serde(rename = "ok")]
+        Ok {},
+        #[serde(rename = "error")]
+        Error { error: String },
+    }
+    let record_id = find_record(token, domain, subdomain, record_type)?.id;
+    let client = reqwest::Client::new();
+    let response: YandexDeleteResponse = client
+        .post("https://pddimp.yandex.ru/api2/admin/dns/del")
+        .header(PddToken(token.to_owned()))
         .query(&[
             ("domain", domain),
             ("record_id", &record_id.to_string()),
-            ("content", &ip.to_string()),
         ])
         .send()?
         .json()?;
     trace!("{:?}", response);
     match response {
-        YandexUpdateResponse::Ok { record } => {
-            info!("IP has been set to {:?}", record.content);
+        YandexDeleteResponse::Ok {} => {
+            info!("Deleted {} record for {:?}", record_type.as_str(), subdomain);
             Ok(())
         }
-        YandexUpdateResponse::Error { error } => bail!("Update failed: {}", error),
+        YandexDeleteResponse::Error { error } => bail!("Delete failed: {}", error),
     }
 }
 
-fn update(token: &str, domain: &str, subdomain: &str) -> Result<(), Error> {
-    let current_ip = current_ip(token, domain, subdomain)?;
-    let real_ip = real_ip()?;
+fn update(
+    token: &str,
+    domain: &str,
+    subdomain: &str,
+    record_type: RecordType,
+    ttl: Option<u32>,
+) -> Result<(), Error> {
+    let current_ip = current_ip(token, domain, subdomain, record_type)?;
+    let real_ip = real_ip(record_type)?;
     if current_ip != real_ip {
         info!(
             "Current {:?} differs from real {:?}, updating",
             current_ip, real_ip
         );
-        set_ip(token, domain, subdomain, real_ip)?;
+        set_ip(token, domain, subdomain, record_type, real_ip, ttl)?;
     } else {
         debug!("Current IP is same as real IP: {:?}", current_ip);
     }
     Ok(())
 }
 
+#[derive(Deserialize, Debug)]
+struct RecordConfig {
+    subdomain: String,
+    #[serde(rename = "type")]
+    record_type: RecordType,
+    #[serde(default)]
+    addr: Option<IpAddr>,
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Config {
+    token: String,
+    domain: String,
+    dns_records: Vec<RecordConfig>,
+    #[serde(default)]
+    delay_between_check: Option<u64>,
+    #[serde(default)]
+    use_resolver: bool,
+}
+
+impl Config {
+    fn load(path: &Path) -> Result<Config, Error> {
+        let config = serde_yaml::from_str(&::std::fs::read_to_string(path)?)?;
+        Ok(config)
+    }
+}
+
+/// Default interval in seconds between checks when none is configured.
+const DEFAULT_INTERVAL: u64 = 300;
+
+fn sync_record(config: &Config, record: &RecordConfig) -> Result<(), Error> {
+    debug!(
+        "domain={:?}, subdomain={:?}, type={:?}",
+        config.domain, record.subdomain, record.record_type
+    );
+    match record.addr {
+        Some(addr) => set_ip(
+            &config.token,
+            &config.domain,
+            &record.subdomain,
+            record.record_type,
+            addr,
+            record.ttl,
+        ),
+        None if config.use_resolver => update_resolved(config, record),
+        None => update(
+            &config.token,
+            &config.domain,
+            &record.subdomain,
+            record.record_type,
+            record.ttl,
+        ),
+    }
+}
+
 #[derive(StructOpt)]
 #[structopt(name = "ypdddns", about = "Yandex PDD Dynamic DNS")]
 enum Options {
+    #[structopt(name = "config")]
+    Config {
+        #[structopt(parse(from_os_str))]
+        config: PathBuf,
+    },
+    #[structopt(name = "watch")]
+    Watch {
+        #[structopt(parse(from_os_str))]
+        config: PathBuf,
+        #[structopt(long = "interval")]
+        interval: Option<u64>,
+    },
     #[structopt(name = "set")]
     Set {
+        #[structopt(long = "token", env = "PDD_TOKEN", hide_env_values = true)]
         token: String,
+        #[structopt(long = "domain", env = "YPDDDNS_DOMAIN")]
         domain: String,
         value: IpAddr,
+        #[structopt(long = "ttl")]
+        ttl: Option<u32>,
     },
     #[structopt(name = "update")]
-    Update { token: String, domain: String },
+    Update {
+        #[structopt(long = "token", env = "PDD_TOKEN", hide_env_values = true)]
+        token: String,
+        #[structopt(long = "domain", env = "YPDDDNS_DOMAIN")]
+        domain: String,
+        #[structopt(long = "type", default_value = "A")]
+        record_type: RecordType,
+    },
+    #[structopt(name = "list")]
+    List {
+        #[structopt(long = "token", env = "PDD_TOKEN", hide_env_values = true)]
+        token: String,
+        #[structopt(long = "domain", env = "YPDDDNS_DOMAIN")]
+        domain: String,
+    },
+    #[structopt(name = "add")]
+    Add {
+        #[structopt(long = "token", env = "PDD_TOKEN", hide_env_values = true)]
+        token: String,
+        #[structopt(long = "domain", env = "YPDDDNS_DOMAIN")]
+        domain: String,
+        value: IpAddr,
+        #[structopt(long = "ttl")]
+        ttl: Option<u32>,
+    },
+    #[structopt(name = "delete")]
+    Delete {
+        #[structopt(long = "token", env = "PDD_TOKEN", hide_env_values = true)]
+        token: String,
+        #[structopt(long = "domain", env = "YPDDDNS_DOMAIN")]
+        domain: String,
+        #[structopt(long = "type", default_value = "A")]
+        record_type: RecordType,
+    },
 }
 
 fn main() -> Result<(), Error> {
+    dotenv::dotenv().ok();
     env_logger::try_init()?;
     let options: Options = structopt::StructOpt::from_args();
+    if let Options::Config { config } = &options {
+        let config = Config::load(config)?;
+        for record in &config.dns_records {
+            sync_record(&config, record)?;
+        }
+        return Ok(());
+    }
+    if let Options::Watch { config, interval } = &options {
+        let config = Config::load(config)?;
+        let interval = interval
+            .or(config.delay_between_check)
+            .unwrap_or(DEFAULT_INTERVAL);
+        info!("Watching every {} seconds", interval);
+        loop {
+            info!("Running check for {} record(s)", config.dns_records.len());
+            for record in &config.dns_records {
+                if let Err(error) = sync_record(&config, record) {
+                    error!("Check failed for {:?}: {}", record.subdomain, error);
+                }
+            }
+            ::std::thread::sleep(::std::time::Duration::from_secs(interval));
+        }
+    }
+    if let Options::List { token, domain } = &options {
+        for record in find_all_records(token, domain)? {
+            println!(
+                "{}\t{}\t{}\tttl={}",
+                record.record_type.as_str(),
+                record.subdomain,
+                record.content,
+                record.ttl
+            );
+        }
+        return Ok(());
+    }
     let (token, subdomain, domain) = match &options {
-        Options::Set { token, domain, .. } | Options::Update { token, domain } => {
+        Options::Set { token, domain, .. }
+        | Options::Update { token, domain, .. }
+        | Options::Add { token, domain, .. }
+        | Options::Delete { token, domain, .. } => {
             let first_point = match domain.find('.') {
                 Some(idx) => idx,
                 None => bail!("domain doesn't contain '.'"),
             };
             (token, &domain[..first_point], &domain[first_point + 1..])
         }
+        Options::Config { .. } | Options::Watch { .. } | Options::List { .. } => unreachable!(),
     };
     debug!("domain={:?}, subdomain={:?}", domain, subdomain);
     match &options {
-        Options::Set { value, .. } => {
-            set_ip(token, domain, subdomain, *value)?;
+        Options::Set { value, ttl, .. } => {
+            set_ip(token, domain, subdomain, RecordType::of_ip(value), *value, *ttl)?;
+        }
+        Options::Update { record_type, .. } => {
+            update(token, domain, subdomain, *record_type, None)?;
+        }
+        Options::Add { value, ttl, .. } => {
+            add_record(token, domain, subdomain, RecordType::of_ip(value), *value, *ttl)?;
         }
-        Options::Update { .. } => {
-            update(token, domain, subdomain)?;
+        Options::Delete { record_type, .. } => {
+            delete_record(token, domain, subdomain, *record_type)?;
         }
+        Options::Config { .. } | Options::Watch { .. } | Options::List { .. } => unreachable!(),
     }
     Ok(())
 }